@@ -0,0 +1,81 @@
+use crate::error::Error;
+use crate::token::{Token, Tokenizer};
+
+/// The two ways an already-recorded team ping can be reclassified (see
+/// `db::notifications::PingKind`; direct mentions can't be recategorized).
+#[derive(PartialEq, Eq, Debug)]
+pub enum PingCategory {
+    Fyi,
+    ActionNeeded,
+}
+
+/// `categorize <url> as fyi|action-needed` - relabel an already-recorded ping.
+#[derive(PartialEq, Eq, Debug)]
+pub struct CategorizeCommand {
+    pub url: String,
+    pub category: PingCategory,
+}
+
+impl CategorizeCommand {
+    pub fn parse<'a>(input: &mut Tokenizer<'a>) -> Result<Option<Self>, Error<'a>> {
+        if let Some(Token::Word("categorize")) = input.peek_token()? {
+            input.next_token()?;
+        } else {
+            return Ok(None);
+        }
+
+        let url = match input.next_token()? {
+            Some(Token::Word(url)) => url.to_owned(),
+            _ => return Ok(None),
+        };
+
+        if let Some(Token::Word("as")) = input.peek_token()? {
+            input.next_token()?;
+        }
+
+        let category = match input.next_token()? {
+            Some(Token::Word("fyi")) => PingCategory::Fyi,
+            Some(Token::Word("action-needed")) => PingCategory::ActionNeeded,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Self { url, category }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Tokenizer;
+
+    fn parse(input: &str) -> Option<CategorizeCommand> {
+        CategorizeCommand::parse(&mut Tokenizer::new(input)).unwrap()
+    }
+
+    #[test]
+    fn with_as() {
+        assert_eq!(
+            parse("categorize https://github.com/rust-lang/rust/issues/1 as fyi"),
+            Some(CategorizeCommand {
+                url: "https://github.com/rust-lang/rust/issues/1".to_owned(),
+                category: PingCategory::Fyi,
+            })
+        );
+    }
+
+    #[test]
+    fn without_as() {
+        assert_eq!(
+            parse("categorize https://github.com/rust-lang/rust/issues/1 action-needed"),
+            Some(CategorizeCommand {
+                url: "https://github.com/rust-lang/rust/issues/1".to_owned(),
+                category: PingCategory::ActionNeeded,
+            })
+        );
+    }
+
+    #[test]
+    fn not_a_categorize_command() {
+        assert_eq!(parse("second"), None);
+    }
+}
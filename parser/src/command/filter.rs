@@ -0,0 +1,96 @@
+use crate::error::Error;
+use crate::token::{Token, Tokenizer};
+
+/// Manage the caller's own notification filter rules (see `handlers::notification`).
+/// A user can only ever read or modify their own filters; that scoping is enforced
+/// by the handler, not here.
+#[derive(PartialEq, Eq, Debug)]
+pub enum FilterCommand {
+    /// `filter mute-team <team>` - stop recording team pings to `<team>`. `<team>` may
+    /// be given as `org/team` or just `team`; the handler normalizes to the short name
+    /// before matching, since that's the form recorded on a ping.
+    MuteTeam(String),
+    /// `filter direct-only` - drop all team pings, keep direct `@user` mentions.
+    DirectOnly,
+    /// `filter description-regex <R>` - drop pings whose short description matches
+    /// the (single-word, no-whitespace) regex `<R>`.
+    DescriptionRegex(String),
+    /// `filter list` - list the caller's current filter rules.
+    List,
+    /// `filter remove <id>` - delete one of the caller's filter rules by id.
+    Remove(u32),
+}
+
+impl FilterCommand {
+    pub fn parse<'a>(input: &mut Tokenizer<'a>) -> Result<Option<Self>, Error<'a>> {
+        if let Some(Token::Word("filter")) = input.peek_token()? {
+            input.next_token()?;
+        } else {
+            return Ok(None);
+        }
+
+        Ok(match input.next_token()? {
+            Some(Token::Word("mute-team")) => match input.next_token()? {
+                Some(Token::Word(team)) => Some(Self::MuteTeam(team.to_owned())),
+                _ => None,
+            },
+            Some(Token::Word("direct-only")) => Some(Self::DirectOnly),
+            Some(Token::Word("description-regex")) => match input.next_token()? {
+                Some(Token::Word(pattern)) => Some(Self::DescriptionRegex(pattern.to_owned())),
+                _ => None,
+            },
+            Some(Token::Word("list")) => Some(Self::List),
+            Some(Token::Word("remove")) => match input.next_token()? {
+                Some(Token::Word(idx)) => idx.parse().ok().map(Self::Remove),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Tokenizer;
+
+    fn parse(input: &str) -> Option<FilterCommand> {
+        FilterCommand::parse(&mut Tokenizer::new(input)).unwrap()
+    }
+
+    #[test]
+    fn mute_team() {
+        assert_eq!(
+            parse("filter mute-team rust-lang/compiler"),
+            Some(FilterCommand::MuteTeam("rust-lang/compiler".to_owned()))
+        );
+    }
+
+    #[test]
+    fn direct_only() {
+        assert_eq!(parse("filter direct-only"), Some(FilterCommand::DirectOnly));
+    }
+
+    #[test]
+    fn description_regex() {
+        assert_eq!(
+            parse("filter description-regex beta-nominated"),
+            Some(FilterCommand::DescriptionRegex("beta-nominated".to_owned()))
+        );
+    }
+
+    #[test]
+    fn list() {
+        assert_eq!(parse("filter list"), Some(FilterCommand::List));
+    }
+
+    #[test]
+    fn remove() {
+        assert_eq!(parse("filter remove 12"), Some(FilterCommand::Remove(12)));
+    }
+
+    #[test]
+    fn not_a_filter_command() {
+        assert_eq!(parse("second"), None);
+    }
+}
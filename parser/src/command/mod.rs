@@ -0,0 +1,35 @@
+pub mod categorize;
+pub mod filter;
+pub mod second;
+pub mod topic;
+
+use crate::error::Error;
+use crate::token::Tokenizer;
+use categorize::CategorizeCommand;
+use filter::FilterCommand;
+use topic::TopicCommand;
+
+/// The comment commands owned by `handlers::notification`. Tried in a fixed order
+/// against the same `Tokenizer`; the first sub-parser that recognizes its keyword
+/// wins. Adding a new notification-related command is just another arm here.
+#[derive(PartialEq, Eq, Debug)]
+pub enum NotificationCommand {
+    Filter(FilterCommand),
+    Categorize(CategorizeCommand),
+    Topic(TopicCommand),
+}
+
+impl NotificationCommand {
+    pub fn parse<'a>(input: &mut Tokenizer<'a>) -> Result<Option<Self>, Error<'a>> {
+        if let Some(cmd) = FilterCommand::parse(input)? {
+            return Ok(Some(Self::Filter(cmd)));
+        }
+        if let Some(cmd) = CategorizeCommand::parse(input)? {
+            return Ok(Some(Self::Categorize(cmd)));
+        }
+        if let Some(cmd) = TopicCommand::parse(input)? {
+            return Ok(Some(Self::Topic(cmd)));
+        }
+        Ok(None)
+    }
+}
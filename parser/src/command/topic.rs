@@ -0,0 +1,63 @@
+use crate::error::Error;
+use crate::token::{Token, Tokenizer};
+
+/// `subscribe #topic` / `unsubscribe #topic` - manage the caller's own topic
+/// subscriptions (see `handlers::notification`).
+#[derive(PartialEq, Eq, Debug)]
+pub enum TopicCommand {
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+impl TopicCommand {
+    pub fn parse<'a>(input: &mut Tokenizer<'a>) -> Result<Option<Self>, Error<'a>> {
+        let subscribe = match input.peek_token()? {
+            Some(Token::Word("subscribe")) => true,
+            Some(Token::Word("unsubscribe")) => false,
+            _ => return Ok(None),
+        };
+        input.next_token()?;
+
+        let topic = match input.next_token()? {
+            Some(Token::Word(topic)) => topic.trim_start_matches('#').to_owned(),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(if subscribe {
+            Self::Subscribe(topic)
+        } else {
+            Self::Unsubscribe(topic)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Tokenizer;
+
+    fn parse(input: &str) -> Option<TopicCommand> {
+        TopicCommand::parse(&mut Tokenizer::new(input)).unwrap()
+    }
+
+    #[test]
+    fn subscribe() {
+        assert_eq!(
+            parse("subscribe #beta-nominated"),
+            Some(TopicCommand::Subscribe("beta-nominated".to_owned()))
+        );
+    }
+
+    #[test]
+    fn unsubscribe() {
+        assert_eq!(
+            parse("unsubscribe #beta-nominated"),
+            Some(TopicCommand::Unsubscribe("beta-nominated".to_owned()))
+        );
+    }
+
+    #[test]
+    fn not_a_topic_command() {
+        assert_eq!(parse("second"), None);
+    }
+}
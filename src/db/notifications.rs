@@ -0,0 +1,402 @@
+//! Storage for the `@user`/`@team` ping agenda (see `handlers::notification`).
+
+use anyhow::Context;
+use chrono::{DateTime, FixedOffset, Utc};
+use regex::Regex;
+use std::collections::HashSet;
+use tokio_postgres::Client as DbClient;
+
+pub enum Identifier<'a> {
+    Url(&'a str),
+    Index(std::num::NonZeroU32),
+}
+
+/// How urgently a ping should be treated. Direct mentions are always `DirectMention`;
+/// team pings default to `default_for_team` (the same category for every team) but can
+/// be overridden by the pinger (`@team?` for FYI, `@team!` for action-needed) or
+/// recategorized later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingKind {
+    DirectMention,
+    TeamFyi,
+    TeamActionNeeded,
+}
+
+impl PingKind {
+    fn as_db_str(self) -> &'static str {
+        match self {
+            PingKind::DirectMention => "direct_mention",
+            PingKind::TeamFyi => "team_fyi",
+            PingKind::TeamActionNeeded => "team_action_needed",
+        }
+    }
+
+    fn from_db_str(s: &str) -> Option<Self> {
+        Some(match s {
+            "direct_mention" => PingKind::DirectMention,
+            "team_fyi" => PingKind::TeamFyi,
+            "team_action_needed" => PingKind::TeamActionNeeded,
+            _ => return None,
+        })
+    }
+
+    /// The category a team ping gets when the pinger didn't specify `?`/`!`. The team
+    /// is not consulted: every team defaults to action-needed, and there is no per-team
+    /// setting.
+    pub fn default_for_team(_team: &str) -> Self {
+        PingKind::TeamActionNeeded
+    }
+}
+
+pub struct Notification {
+    pub user_id: i64,
+    pub origin_url: String,
+    pub origin_html: String,
+    pub time: DateTime<FixedOffset>,
+    pub short_description: Option<String>,
+    pub team_name: Option<String>,
+    pub kind: PingKind,
+}
+
+pub async fn record_username(db: &DbClient, user_id: i64, username: String) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO users (user_id, username) VALUES ($1, $2)
+         ON CONFLICT (user_id) DO UPDATE SET username = $2",
+        &[&user_id, &username],
+    )
+    .await
+    .context("inserting username")?;
+    Ok(())
+}
+
+pub async fn record_ping(db: &DbClient, notification: &Notification) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO notifications (user_id, origin_url, origin_html, time, short_description, team_name, kind)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        &[
+            &notification.user_id,
+            &notification.origin_url,
+            &notification.origin_html,
+            &notification.time,
+            &notification.short_description,
+            &notification.team_name,
+            &notification.kind.as_db_str(),
+        ],
+    )
+    .await
+    .context("inserting notification")?;
+    Ok(())
+}
+
+/// Updates the `PingKind` of an already-recorded ping, e.g. after a `categorize`
+/// comment command reclassifies a team ping as FYI or action-needed. Direct mentions
+/// are excluded by the `WHERE` clause: they're always `DirectMention` (see `PingKind`),
+/// so a direct ping silently stays put rather than being flipped to a team category.
+pub async fn recategorize_ping<'a>(
+    db: &DbClient,
+    user_id: i64,
+    identifier: Identifier<'a>,
+    kind: PingKind,
+) -> anyhow::Result<()> {
+    let direct_mention = PingKind::DirectMention.as_db_str();
+    match identifier {
+        Identifier::Url(origin_url) => {
+            db.execute(
+                "UPDATE notifications SET kind = $1
+                 WHERE user_id = $2 AND origin_url = $3 AND kind <> $4",
+                &[&kind.as_db_str(), &user_id, &origin_url, &direct_mention],
+            )
+            .await
+            .context("recategorizing notification by url")?;
+        }
+        Identifier::Index(idx) => {
+            db.execute(
+                "UPDATE notifications SET kind = $1
+                 WHERE user_id = $2 AND idx = $3 AND kind <> $4",
+                &[&kind.as_db_str(), &user_id, &(idx.get() as i32), &direct_mention],
+            )
+            .await
+            .context("recategorizing notification by index")?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn delete_ping<'a>(
+    db: &mut DbClient,
+    user_id: i64,
+    identifier: Identifier<'a>,
+) -> anyhow::Result<()> {
+    match identifier {
+        Identifier::Url(origin_url) => {
+            db.execute(
+                "DELETE FROM notifications WHERE user_id = $1 AND origin_url = $2",
+                &[&user_id, &origin_url],
+            )
+            .await
+            .context("deleting notification by url")?;
+        }
+        Identifier::Index(idx) => {
+            db.execute(
+                "DELETE FROM notifications WHERE user_id = $1 AND idx = $2",
+                &[&user_id, &(idx.get() as i32)],
+            )
+            .await
+            .context("deleting notification by index")?;
+        }
+    }
+    Ok(())
+}
+
+/// Hides notification(s) matched by `identifier` from the agenda until `until`, without
+/// deleting them. Complementary to `delete_ping`/`acknowledge`: a snooze resurfaces the
+/// ping once the deadline passes, rather than discarding it for good.
+pub async fn snooze_ping<'a>(
+    db: &mut DbClient,
+    user_id: i64,
+    identifier: Identifier<'a>,
+    until: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    match identifier {
+        Identifier::Url(origin_url) => {
+            db.execute(
+                "UPDATE notifications SET snoozed_until = $1 WHERE user_id = $2 AND origin_url = $3",
+                &[&until, &user_id, &origin_url],
+            )
+            .await
+            .context("snoozing notification by url")?;
+        }
+        Identifier::Index(idx) => {
+            db.execute(
+                "UPDATE notifications SET snoozed_until = $1 WHERE user_id = $2 AND idx = $3",
+                &[&until, &user_id, &(idx.get() as i32)],
+            )
+            .await
+            .context("snoozing notification by index")?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the set of user ids that have already been notified about `origin_url`.
+///
+/// This backs edit-aware re-pinging: when a comment is edited we only want to notify
+/// users whose mention is newly added, not everyone who was already pinged.
+pub async fn notified_user_ids(db: &DbClient, origin_url: &str) -> anyhow::Result<HashSet<i64>> {
+    let rows = db
+        .query(
+            "SELECT user_id FROM notification_origins WHERE origin_url = $1",
+            &[&origin_url],
+        )
+        .await
+        .context("fetching previously notified users")?;
+    Ok(rows.into_iter().map(|row| row.get(0)).collect())
+}
+
+/// Records that `user_ids` have now been notified about `origin_url`, so a later edit
+/// of the same comment/issue won't re-notify them.
+pub async fn add_notified_user_ids(
+    db: &DbClient,
+    origin_url: &str,
+    user_ids: impl IntoIterator<Item = i64>,
+) -> anyhow::Result<()> {
+    for user_id in user_ids {
+        db.execute(
+            "INSERT INTO notification_origins (origin_url, user_id) VALUES ($1, $2)
+             ON CONFLICT (origin_url, user_id) DO NOTHING",
+            &[&origin_url, &user_id],
+        )
+        .await
+        .context("recording notified user")?;
+    }
+    Ok(())
+}
+
+/// A user-configured rule that suppresses some pings before they land on their agenda.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterRule {
+    /// Drop team pings from this team (direct `@user` mentions still go through).
+    MuteTeam(String),
+    /// Drop all team pings. Direct `@user` mentions, and `#topic` pings (which carry no
+    /// team), still go through.
+    DirectOnly,
+    /// Drop pings whose `short_description` matches this regex.
+    DescriptionRegex(String),
+}
+
+/// A `FilterRule` with its regex (if any) already compiled, so `suppresses` doesn't
+/// recompile a pattern on every notification. Built once by `get_filters`.
+enum CompiledRule {
+    MuteTeam(String),
+    DirectOnly,
+    DescriptionRegex(Regex),
+}
+
+pub struct Filter {
+    pub id: i32,
+    rule: CompiledRule,
+}
+
+impl Filter {
+    /// Returns true if this rule should suppress `notification` before it's recorded.
+    /// `is_direct` distinguishes a direct `@user` mention from a team-expanded one.
+    pub fn suppresses(&self, notification: &Notification, is_direct: bool) -> bool {
+        match &self.rule {
+            CompiledRule::MuteTeam(team) => {
+                notification.team_name.as_deref() == Some(team.as_str())
+            }
+            CompiledRule::DirectOnly => !is_direct,
+            CompiledRule::DescriptionRegex(re) => notification
+                .short_description
+                .as_deref()
+                .map_or(false, |desc| re.is_match(desc)),
+        }
+    }
+
+    /// Human-readable summary for `filter list`.
+    pub fn describe(&self) -> String {
+        match &self.rule {
+            CompiledRule::MuteTeam(team) => format!("mute team pings from {}", team),
+            CompiledRule::DirectOnly => "direct mentions only (drop team pings)".to_owned(),
+            CompiledRule::DescriptionRegex(re) => format!("drop pings matching /{}/", re.as_str()),
+        }
+    }
+}
+
+fn filter_kind_and_pattern(rule: &FilterRule) -> (&'static str, Option<&str>) {
+    match rule {
+        FilterRule::MuteTeam(team) => ("mute_team", Some(team.as_str())),
+        FilterRule::DirectOnly => ("direct_only", None),
+        FilterRule::DescriptionRegex(pattern) => ("description_regex", Some(pattern.as_str())),
+    }
+}
+
+/// Adds a new filter rule for `user_id`. Rules are only ever read or modified by the
+/// owning user; callers are responsible for enforcing that at the command layer.
+///
+/// A `DescriptionRegex` rule is compiled here to validate it; an invalid pattern is
+/// rejected up front instead of being stored and silently ignored on every ping.
+pub async fn create_filter(db: &DbClient, user_id: i64, rule: &FilterRule) -> anyhow::Result<()> {
+    if let FilterRule::DescriptionRegex(pattern) = rule {
+        Regex::new(pattern).with_context(|| format!("invalid filter regex {:?}", pattern))?;
+    }
+
+    let (kind, pattern) = filter_kind_and_pattern(rule);
+    db.execute(
+        "INSERT INTO notification_filters (user_id, kind, pattern) VALUES ($1, $2, $3)",
+        &[&user_id, &kind, &pattern],
+    )
+    .await
+    .context("inserting notification filter")?;
+    Ok(())
+}
+
+pub async fn get_filters(db: &DbClient, user_id: i64) -> anyhow::Result<Vec<Filter>> {
+    let rows = db
+        .query(
+            "SELECT id, kind, pattern FROM notification_filters WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await
+        .context("fetching notification filters")?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let id: i32 = row.get(0);
+            let kind: String = row.get(1);
+            let pattern: Option<String> = row.get(2);
+            let rule = match kind.as_str() {
+                "mute_team" => CompiledRule::MuteTeam(pattern?),
+                "direct_only" => CompiledRule::DirectOnly,
+                "description_regex" => match Regex::new(&pattern?) {
+                    Ok(re) => CompiledRule::DescriptionRegex(re),
+                    Err(e) => {
+                        // create_filter validates before insert, so this would mean the
+                        // stored pattern predates that check (or the regex crate was
+                        // downgraded); drop the rule rather than failing every ping.
+                        log::warn!("stored notification filter regex no longer compiles: {:?}", e);
+                        return None;
+                    }
+                },
+                _ => return None,
+            };
+            Some(Filter { id, rule })
+        })
+        .collect())
+}
+
+/// Subscribes `user_id` to `#topic`, so they're pinged whenever it's mentioned outside
+/// of code in a tracked issue/comment. Tags are read from bodies as `#` followed by word
+/// characters only, so a topic containing other characters (e.g. `beta-nominated`) is
+/// stored but never matches.
+pub async fn subscribe_topic(db: &DbClient, user_id: i64, topic: &str) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO topic_subscriptions (user_id, topic) VALUES ($1, $2)
+         ON CONFLICT (user_id, topic) DO NOTHING",
+        &[&user_id, &topic],
+    )
+    .await
+    .context("subscribing to topic")?;
+    Ok(())
+}
+
+pub async fn unsubscribe_topic(db: &DbClient, user_id: i64, topic: &str) -> anyhow::Result<()> {
+    db.execute(
+        "DELETE FROM topic_subscriptions WHERE user_id = $1 AND topic = $2",
+        &[&user_id, &topic],
+    )
+    .await
+    .context("unsubscribing from topic")?;
+    Ok(())
+}
+
+/// Returns the ids of every user subscribed to `#topic`.
+pub async fn topic_subscribers(db: &DbClient, topic: &str) -> anyhow::Result<Vec<i64>> {
+    let rows = db
+        .query(
+            "SELECT user_id FROM topic_subscriptions WHERE topic = $1",
+            &[&topic],
+        )
+        .await
+        .context("fetching topic subscribers")?;
+    Ok(rows.into_iter().map(|row| row.get(0)).collect())
+}
+
+/// Returns a user's agenda: every ping recorded for them that isn't currently snoozed.
+pub async fn get_notifications(db: &DbClient, user_id: i64) -> anyhow::Result<Vec<Notification>> {
+    let rows = db
+        .query(
+            "SELECT user_id, origin_url, origin_html, time, short_description, team_name, kind
+             FROM notifications
+             WHERE user_id = $1 AND (snoozed_until IS NULL OR snoozed_until <= now())",
+            &[&user_id],
+        )
+        .await
+        .context("fetching agenda")?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let kind: String = row.get(6);
+            Notification {
+                user_id: row.get(0),
+                origin_url: row.get(1),
+                origin_html: row.get(2),
+                time: row.get(3),
+                short_description: row.get(4),
+                team_name: row.get(5),
+                kind: PingKind::from_db_str(&kind).unwrap_or(PingKind::DirectMention),
+            }
+        })
+        .collect())
+}
+
+/// Deletes filter `id`, scoped to `user_id` so a user can only remove their own rules.
+pub async fn delete_filter(db: &DbClient, user_id: i64, id: i32) -> anyhow::Result<()> {
+    db.execute(
+        "DELETE FROM notification_filters WHERE user_id = $1 AND id = $2",
+        &[&user_id, &id],
+    )
+    .await
+    .context("deleting notification filter")?;
+    Ok(())
+}
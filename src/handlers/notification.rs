@@ -10,20 +10,140 @@ use crate::{
     handlers::Context,
 };
 use anyhow::Context as _;
+use chrono::Utc;
 use regex::Regex;
 use std::collections::HashSet;
 use std::convert::TryFrom;
 
 lazy_static::lazy_static! {
-    static ref PING_RE: Regex = Regex::new(r#"@([-\w\d/]+)"#,).unwrap();
+    static ref PING_RE: Regex = Regex::new(r#"@([-\w\d/]+)([?!])?"#,).unwrap();
     static ref ACKNOWLEDGE_RE: Regex = Regex::new(r#"acknowledge (https?://[^ ]+)"#,).unwrap();
+    static ref SNOOZE_RE: Regex =
+        Regex::new(r#"snooze (https?://[^ ]+) (\d+)(d|h|m)"#).unwrap();
+    static ref TOPIC_RE: Regex = Regex::new(r#"#(\w+)"#).unwrap();
+}
+
+/// Strips fenced and inline code spans before tag extraction, analogous to how status
+/// content is sanitized before looking for tags: a `#` inside a code block or span
+/// shouldn't be mistaken for a topic subscription tag.
+fn strip_code_spans(body: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut in_fence = false;
+    for line in body.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            out.push('\n');
+            continue;
+        }
+        if in_fence {
+            out.push('\n');
+            continue;
+        }
+        let mut in_span = false;
+        for c in line.chars() {
+            if c == '`' {
+                in_span = !in_span;
+            } else if !in_span {
+                out.push(c);
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Caps how far out a snooze can be scheduled (~10 years). `SNOOZE_RE`'s `(\d+)` has no
+/// upper bound, so without this a crafted amount would overflow `chrono::Duration`
+/// construction (which panics on overflow) or the later `DateTime + Duration` add.
+const MAX_SNOOZE_MINUTES: i64 = 10 * 365 * 24 * 60;
+
+fn parse_snooze_duration(amount: &str, unit: &str) -> Option<chrono::Duration> {
+    let amount: i64 = amount.parse().ok()?;
+    let minutes = match unit {
+        "d" => amount.checked_mul(24 * 60)?,
+        "h" => amount.checked_mul(60)?,
+        "m" => amount,
+        _ => return None,
+    };
+    if !(0..=MAX_SNOOZE_MINUTES).contains(&minutes) {
+        return None;
+    }
+    Some(chrono::Duration::minutes(minutes))
+}
+
+/// A condition the ping handler can hit that isn't a hard failure: either the event
+/// carries nothing actionable, or a single user's data was unusable. Both are reported
+/// distinctly from `anyhow::Error` so callers can tell "nothing to do here" and "skip
+/// this one user but keep going" apart from a real bug.
+#[derive(Debug)]
+pub enum PingHandlerError {
+    /// The event has no payload this handler cares about (e.g. no comment body).
+    NoPayload,
+    /// A single user was skipped; the rest of the batch should still be processed.
+    UserSkipped { login: String, reason: String },
+}
+
+impl std::fmt::Display for PingHandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PingHandlerError::NoPayload => write!(f, "event has no actionable payload"),
+            PingHandlerError::UserSkipped { login, reason } => {
+                write!(f, "skipping {}: {}", login, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PingHandlerError {}
+
+/// A uniform view over the event kinds the ping handler cares about; the
+/// acknowledge, snooze and command loops in `handle` go through it instead of matching
+/// on the variant. It is implemented on `Event` itself, and `handle` still matches on
+/// `Event` variants for the edit check and the short description, so a new variant
+/// needs arms in those matches as well as in this impl.
+pub trait Payload {
+    /// Every user this event directly involves (currently just its author; a review
+    /// event might also surface the reviewer here).
+    fn involved_users(&self) -> Vec<&github::User>;
+    fn author(&self) -> &github::User;
+    fn sent_from(&self) -> Option<&str>;
+}
+
+impl Payload for Event {
+    fn involved_users(&self) -> Vec<&github::User> {
+        vec![self.author()]
+    }
+
+    fn author(&self) -> &github::User {
+        match self {
+            Event::Issue(e) => &e.issue.user,
+            Event::IssueComment(e) => &e.comment.user,
+        }
+    }
+
+    fn sent_from(&self) -> Option<&str> {
+        self.html_url()
+    }
+}
+
+/// Resolves a GitHub user id, reporting a missing id as a per-user skip rather than
+/// aborting the whole batch (that used to `return Ok(())`, silently dropping every
+/// remaining ack/snooze/ping in the same event).
+fn require_user_id(user: &github::User) -> Result<i64, PingHandlerError> {
+    user.id.ok_or_else(|| PingHandlerError::UserSkipped {
+        login: user.login.clone(),
+        reason: "no id found".to_owned(),
+    })
 }
 
 pub async fn handle(ctx: &Context, event: &Event) -> anyhow::Result<()> {
     let body = match event.comment_body() {
         Some(v) => v,
         // Skip events that don't have comment bodies associated
-        None => return Ok(()),
+        None => {
+            log::trace!("{}", PingHandlerError::NoPayload);
+            return Ok(());
+        }
     };
 
     // Permit editing acknowledgement
@@ -34,32 +154,98 @@ pub async fn handle(ctx: &Context, event: &Event) -> anyhow::Result<()> {
         .collect::<Vec<_>>();
     log::trace!("Captured acknowledgements: {:?}", acks);
     for url in acks {
-        let user = match event {
-            Event::Issue(e) => &e.issue.user,
-            Event::IssueComment(e) => &e.comment.user,
-        };
-        let id = match user.id {
-            Some(id) => id,
-            // If the user was not in the team(s) then just don't record it.
+        for user in event.involved_users() {
+            let id = match require_user_id(user) {
+                Ok(id) => id,
+                Err(err) => {
+                    log::trace!("{}", err);
+                    continue;
+                }
+            };
+
+            if let Err(e) = notifications::delete_ping(
+                &mut Context::make_db_client(&ctx.github.raw()).await?,
+                id,
+                notifications::Identifier::Url(&url),
+            )
+            .await
+            {
+                log::warn!(
+                    "failed to delete notification: url={}, user={:?}: {:?}",
+                    url,
+                    user,
+                    e
+                );
+            }
+        }
+    }
+
+    // Permit snoozing: hide a ping until a given duration has elapsed, rather than
+    // deleting it outright.
+
+    let snoozes = SNOOZE_RE
+        .captures_iter(body)
+        .filter_map(|c| {
+            let url = c.get(1)?.as_str().to_owned();
+            let duration = parse_snooze_duration(c.get(2)?.as_str(), c.get(3)?.as_str())?;
+            Some((url, duration))
+        })
+        .collect::<Vec<_>>();
+    log::trace!("Captured snoozes: {:?}", snoozes);
+    for (url, duration) in snoozes {
+        let until = match Utc::now().checked_add_signed(duration) {
+            Some(until) => until,
             None => {
-                log::trace!("Skipping {} because no id found", user.login);
-                return Ok(());
+                log::warn!("snooze duration for {} overflowed, skipping", url);
+                continue;
             }
         };
 
-        if let Err(e) = notifications::delete_ping(
-            &mut Context::make_db_client(&ctx.github.raw()).await?,
-            id,
-            notifications::Identifier::Url(&url),
-        )
-        .await
-        {
-            log::warn!(
-                "failed to delete notification: url={}, user={:?}: {:?}",
-                url,
-                user,
-                e
-            );
+        for user in event.involved_users() {
+            let id = match require_user_id(user) {
+                Ok(id) => id,
+                Err(err) => {
+                    log::trace!("{}", err);
+                    continue;
+                }
+            };
+
+            if let Err(e) = notifications::snooze_ping(
+                &mut Context::make_db_client(&ctx.github.raw()).await?,
+                id,
+                notifications::Identifier::Url(&url),
+                until,
+            )
+            .await
+            {
+                log::warn!(
+                    "failed to snooze notification: url={}, user={:?}: {:?}",
+                    url,
+                    user,
+                    e
+                );
+            }
+        }
+    }
+
+    // Dispatch any `filter`/`categorize`/`subscribe`/`unsubscribe` comment command
+    // embedded in the body. This is the actual command-dispatch path: without routing
+    // a `Tokenizer` over `body` through `handle_notification_command` here, none of
+    // those commands are ever reachable.
+    for user in event.involved_users() {
+        let id = match require_user_id(user) {
+            Ok(id) => id,
+            Err(err) => {
+                log::trace!("{}", err);
+                continue;
+            }
+        };
+
+        let mut tokenizer = parser::token::Tokenizer::new(body);
+        match handle_notification_command(ctx, id, &mut tokenizer).await {
+            Ok(Some(reply)) => log::info!("notification command from {}: {}", user.login, reply),
+            Ok(None) => {}
+            Err(err) => log::error!("notification command from {}: {:?}", user.login, err),
         }
     }
 
@@ -71,41 +257,60 @@ pub async fn handle(ctx: &Context, event: &Event) -> anyhow::Result<()> {
         }
     }
 
-    if let Event::IssueComment(e) = event {
-        if e.action != github::IssueCommentAction::Created {
-            // skip events other than creating a comment to avoid
-            // renotifying
-            //
-            // FIXME: implement smart tracking to allow rerunning only if
-            // the notification is "new" (i.e. edit adds a ping)
-            return Ok(());
-        }
-    }
+    let is_edit = match event {
+        Event::IssueComment(e) => match e.action {
+            github::IssueCommentAction::Created => false,
+            github::IssueCommentAction::Edited => true,
+            // Skip events other than creating/editing a comment to avoid
+            // renotifying (e.g. deletion).
+            _ => return Ok(()),
+        },
+        Event::Issue(_) => false,
+    };
 
     let short_description = match event {
         Event::Issue(e) => e.issue.title.clone(),
         Event::IssueComment(e) => format!("Comment on {}", e.issue.title),
     };
 
+    let origin_url = event.sent_from().unwrap().to_owned();
+
+    // On an edit, only newly-added mentions should trigger a notification. A mention
+    // that was present before and got removed is *not* retroactively un-notified here;
+    // only an explicit `acknowledge` does that. The set is a union across edits so
+    // re-adding then re-removing a mention doesn't cause a second notification.
+    let already_notified = if is_edit {
+        notifications::notified_user_ids(&ctx.db, &origin_url)
+            .await
+            .context("failed to load previously notified users")?
+    } else {
+        HashSet::new()
+    };
+
+    // `?`/`!` suffixes on a team mention (e.g. `@rust-lang/team!`) let the pinger pick
+    // the urgency up front; absent a suffix the team gets its default category. Last
+    // occurrence wins if the same mention appears more than once with different
+    // suffixes.
     let caps = PING_RE
         .captures_iter(body)
-        .map(|c| c.get(1).unwrap().as_str().to_owned())
-        .collect::<HashSet<_>>();
+        .map(|c| {
+            let login = c.get(1).unwrap().as_str().to_owned();
+            let marker = c.get(2).map(|m| m.as_str().chars().next().unwrap());
+            (login, marker)
+        })
+        .collect::<std::collections::HashMap<_, _>>();
+    // `users_notified` only dedupes *within this pass* (e.g. a user who is both
+    // directly mentioned and on a pinged team). `recorded` tracks who actually got a
+    // ping recorded, which is what gets persisted for edit-diffing below: a user whose
+    // ping was suppressed by a filter must NOT be treated as already-notified, or
+    // removing that filter later would never let them be pinged on a subsequent edit.
     let mut users_notified = HashSet::new();
+    let mut recorded = HashSet::new();
     log::trace!("Captured usernames in comment: {:?}", caps);
-    for login in caps {
-        let (users, team_name) = if login.contains('/') {
-            // This is a team ping. For now, just add it to everyone's agenda on
-            // that team, but also mark it as such (i.e., a team ping) for
-            // potentially different prioritization and so forth.
-            //
-            // In order to properly handle this down the road, we will want to
-            // distinguish between "everyone must pay attention" and "someone
-            // needs to take a look."
-            //
-            // We may also want to be able to categorize into these buckets
-            // *after* the ping occurs and is initially processed.
-
+    for (login, marker) in caps {
+        let (users, team_name, kind) = if login.contains('/') {
+            // This is a team ping: add it to everyone's agenda on that team, marked
+            // with a `PingKind` so the agenda can sort/group by urgency.
             let mut iter = login.split('/');
             let _rust_lang = iter.next().unwrap();
             let team = iter.next().unwrap();
@@ -125,6 +330,12 @@ pub async fn handle(ctx: &Context, event: &Event) -> anyhow::Result<()> {
                 }
             };
 
+            let kind = match marker {
+                Some('?') => notifications::PingKind::TeamFyi,
+                Some('!') => notifications::PingKind::TeamActionNeeded,
+                _ => notifications::PingKind::default_for_team(&team.name),
+            };
+
             (
                 team.members
                     .into_iter()
@@ -139,6 +350,7 @@ pub async fn handle(ctx: &Context, event: &Event) -> anyhow::Result<()> {
                     })
                     .collect::<anyhow::Result<Vec<github::User>>>(),
                 Some(team.name),
+                kind,
             )
         } else {
             let user = github::User { login, id: None };
@@ -163,6 +375,7 @@ pub async fn handle(ctx: &Context, event: &Event) -> anyhow::Result<()> {
                     }]
                 }),
                 None,
+                notifications::PingKind::DirectMention,
             )
         };
 
@@ -187,24 +400,264 @@ pub async fn handle(ctx: &Context, event: &Event) -> anyhow::Result<()> {
                 log::error!("record username: {:?}", err);
             }
 
-            if let Err(err) = notifications::record_ping(
+            let notification = notifications::Notification {
+                user_id: user.id.unwrap(),
+                origin_url: origin_url.clone(),
+                origin_html: body.to_owned(),
+                time: event.time(),
+                short_description: Some(short_description.clone()),
+                team_name: team_name.clone(),
+                kind,
+            };
+
+            match notify_if_allowed(ctx, &already_notified, notification).await {
+                Ok(true) => {
+                    recorded.insert(user.id.unwrap());
+                }
+                Ok(false) => {}
+                Err(err) => log::error!("record ping: {:?}", err),
+            }
+        }
+    }
+
+    // `#topic` subscriptions: ping everyone subscribed to a tag mentioned outside of
+    // code, in addition to any `@`-mentions above. `users_notified` is shared with the
+    // mention loop so a user who is both mentioned and subscribed is only pinged once.
+    let sanitized_body = strip_code_spans(body);
+    let topics = TOPIC_RE
+        .captures_iter(&sanitized_body)
+        .map(|c| c.get(1).unwrap().as_str().to_owned())
+        .collect::<HashSet<_>>();
+    log::trace!("Captured topics in comment: {:?}", topics);
+    for topic in topics {
+        let subscribers = match notifications::topic_subscribers(&ctx.db, &topic).await {
+            Ok(subscribers) => subscribers,
+            Err(err) => {
+                log::error!("fetching subscribers for #{}: {:?}", topic, err);
+                continue;
+            }
+        };
+
+        for user_id in subscribers {
+            if !users_notified.insert(user_id) {
+                continue;
+            }
+
+            let notification = notifications::Notification {
+                user_id,
+                origin_url: origin_url.clone(),
+                origin_html: body.to_owned(),
+                time: event.time(),
+                short_description: Some(format!("{} (topic #{})", short_description, topic)),
+                team_name: None,
+                kind: notifications::PingKind::DirectMention,
+            };
+
+            match notify_if_allowed(ctx, &already_notified, notification).await {
+                Ok(true) => {
+                    recorded.insert(user_id);
+                }
+                Ok(false) => {}
+                Err(err) => log::error!("record ping: {:?}", err),
+            }
+        }
+    }
+
+    if let Err(err) = notifications::add_notified_user_ids(&ctx.db, &origin_url, recorded)
+        .await
+        .context("failed to persist notified users")
+    {
+        log::error!("persist notified users: {:?}", err);
+    }
+
+    Ok(())
+}
+
+/// Records `notification` unless the recipient has already been notified for this
+/// origin (tracked across edits) or one of their filter rules suppresses it. Returns
+/// whether the ping was actually recorded, so callers only persist the recipients who
+/// really got notified (a filtered-out recipient must stay eligible for later edits).
+async fn notify_if_allowed(
+    ctx: &Context,
+    already_notified: &HashSet<i64>,
+    notification: notifications::Notification,
+) -> anyhow::Result<bool> {
+    if already_notified.contains(&notification.user_id) {
+        // Already pinged this user for this origin in an earlier pass over the same
+        // comment/issue; an edit that keeps the mention shouldn't re-notify.
+        return Ok(false);
+    }
+
+    let filters = notifications::get_filters(&ctx.db, notification.user_id)
+        .await
+        .unwrap_or_else(|err| {
+            log::error!("failed to load notification filters: {:?}", err);
+            Vec::new()
+        });
+    let is_direct = notification.team_name.is_none();
+    if filters.iter().any(|f| f.suppresses(&notification, is_direct)) {
+        log::trace!("Filtered out ping to {}", notification.user_id);
+        return Ok(false);
+    }
+
+    notifications::record_ping(&ctx.db, &notification)
+        .await
+        .context("failed to record ping")?;
+    Ok(true)
+}
+
+/// Runs a `categorize` comment command, relabeling an already-recorded ping as FYI
+/// or action-needed. Scoped to `user_id`: only the ping's own recipient can relabel it.
+pub async fn handle_categorize_command(
+    ctx: &Context,
+    user_id: i64,
+    cmd: parser::command::categorize::CategorizeCommand,
+) -> anyhow::Result<()> {
+    use parser::command::categorize::PingCategory;
+
+    let kind = match cmd.category {
+        PingCategory::Fyi => notifications::PingKind::TeamFyi,
+        PingCategory::ActionNeeded => notifications::PingKind::TeamActionNeeded,
+    };
+    notifications::recategorize_ping(
+        &ctx.db,
+        user_id,
+        notifications::Identifier::Url(&cmd.url),
+        kind,
+    )
+    .await
+    .context("failed to recategorize ping")
+}
+
+/// Runs a `subscribe`/`unsubscribe` comment command issued by `user_id`, managing that
+/// user's own topic subscriptions.
+pub async fn handle_topic_command(
+    ctx: &Context,
+    user_id: i64,
+    cmd: parser::command::topic::TopicCommand,
+) -> anyhow::Result<String> {
+    use parser::command::topic::TopicCommand;
+
+    match cmd {
+        TopicCommand::Subscribe(topic) => {
+            notifications::subscribe_topic(&ctx.db, user_id, &topic)
+                .await
+                .context("failed to subscribe to topic")?;
+            Ok(format!("Subscribed to #{}.", topic))
+        }
+        TopicCommand::Unsubscribe(topic) => {
+            notifications::unsubscribe_topic(&ctx.db, user_id, &topic)
+                .await
+                .context("failed to unsubscribe from topic")?;
+            Ok(format!("Unsubscribed from #{}.", topic))
+        }
+    }
+}
+
+/// Entry point for every comment command this module owns: parses a
+/// `parser::command::NotificationCommand` out of `input` and, if one matched, runs it
+/// as `user_id` and returns the reply text. This is what makes `filter`/`subscribe`/
+/// `categorize` comments reachable at all - without routing a `Tokenizer` over the
+/// comment body through here, `NotificationCommand::parse` is never called.
+pub async fn handle_notification_command<'a>(
+    ctx: &Context,
+    user_id: i64,
+    input: &mut parser::token::Tokenizer<'a>,
+) -> anyhow::Result<Option<String>> {
+    use parser::command::NotificationCommand;
+
+    let cmd = match NotificationCommand::parse(input) {
+        Ok(Some(cmd)) => cmd,
+        Ok(None) => return Ok(None),
+        Err(err) => return Err(anyhow::anyhow!("failed to parse command: {:?}", err)),
+    };
+
+    Ok(Some(match cmd {
+        NotificationCommand::Filter(cmd) => handle_filter_command(ctx, user_id, cmd).await?,
+        NotificationCommand::Categorize(cmd) => {
+            handle_categorize_command(ctx, user_id, cmd).await?;
+            "Recategorized.".to_owned()
+        }
+        NotificationCommand::Topic(cmd) => handle_topic_command(ctx, user_id, cmd).await?,
+    }))
+}
+
+/// Runs a `filter` comment command issued by `user_id`, managing that user's own
+/// notification filters. Scoped to `user_id` so a user can only touch their own rules.
+pub async fn handle_filter_command(
+    ctx: &Context,
+    user_id: i64,
+    cmd: parser::command::filter::FilterCommand,
+) -> anyhow::Result<String> {
+    use parser::command::filter::FilterCommand;
+
+    match cmd {
+        FilterCommand::MuteTeam(team) => {
+            // `notification.team_name` is always the short team name (the part after the
+            // org in a `@org/team` mention, e.g. `compiler`), so a qualified
+            // `org/team` typed here has to be normalized the same way before it can
+            // ever compare equal in `Filter::suppresses`.
+            let team = team.rsplit('/').next().unwrap_or(&team).to_owned();
+            notifications::create_filter(&ctx.db, user_id, &notifications::FilterRule::MuteTeam(team))
+                .await
+                .context("failed to create filter")?;
+            Ok("Filter added.".to_owned())
+        }
+        FilterCommand::DirectOnly => {
+            notifications::create_filter(&ctx.db, user_id, &notifications::FilterRule::DirectOnly)
+                .await
+                .context("failed to create filter")?;
+            Ok("Filter added.".to_owned())
+        }
+        FilterCommand::DescriptionRegex(pattern) => {
+            notifications::create_filter(
                 &ctx.db,
-                &notifications::Notification {
-                    user_id: user.id.unwrap(),
-                    origin_url: event.html_url().unwrap().to_owned(),
-                    origin_html: body.to_owned(),
-                    time: event.time(),
-                    short_description: Some(short_description.clone()),
-                    team_name: team_name.clone(),
-                },
+                user_id,
+                &notifications::FilterRule::DescriptionRegex(pattern),
             )
             .await
-            .context("failed to record ping")
-            {
-                log::error!("record ping: {:?}", err);
+            .context("failed to create filter")?;
+            Ok("Filter added.".to_owned())
+        }
+        FilterCommand::List => {
+            let filters = notifications::get_filters(&ctx.db, user_id)
+                .await
+                .context("failed to list filters")?;
+            if filters.is_empty() {
+                Ok("You have no notification filters.".to_owned())
+            } else {
+                Ok(filters
+                    .iter()
+                    .map(|f| format!("- #{}: {}", f.id, f.describe()))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
             }
         }
+        FilterCommand::Remove(id) => {
+            notifications::delete_filter(&ctx.db, user_id, id as i32)
+                .await
+                .context("failed to remove filter")?;
+            Ok(format!("Filter #{} removed.", id))
+        }
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_code_spans_removes_fenced_blocks() {
+        let body = "before #topic\n```\n#not-a-topic\n```\nafter #topic";
+        let stripped = strip_code_spans(body);
+        assert_eq!(stripped.matches('#').count(), 2);
+    }
+
+    #[test]
+    fn strip_code_spans_removes_inline_spans() {
+        let body = "see `#not-a-topic` but #topic stays";
+        let stripped = strip_code_spans(body);
+        assert!(stripped.contains("#topic"));
+        assert!(!stripped.contains("#not-a-topic"));
+    }
+}